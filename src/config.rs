@@ -0,0 +1,123 @@
+use serde::Deserialize;
+use std::fs;
+
+fn default_rate() -> i32 {
+    11025
+}
+
+fn default_fft_size() -> usize {
+    800
+}
+
+fn default_waterfall_rows() -> usize {
+    80
+}
+
+fn default_waterfall_columns() -> usize {
+    default_fft_size() / 2
+}
+
+fn default_visualisation() -> String {
+    "kaleidoscope".to_string()
+}
+
+fn default_window() -> String {
+    "hann".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AudioConfig {
+    #[serde(default = "default_rate")]
+    pub rate: i32,
+    #[serde(default = "default_fft_size")]
+    pub fft_size: usize,
+    /// Window function applied to each FFT frame: "hann", "hamming" or "rectangular".
+    #[serde(default = "default_window")]
+    pub window: String,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            rate: default_rate(),
+            fft_size: default_fft_size(),
+            window: default_window(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DisplayConfig {
+    #[serde(default)]
+    pub fullscreen: bool,
+    #[serde(default = "default_visualisation")]
+    pub default_visualisation: String,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        DisplayConfig {
+            fullscreen: false,
+            default_visualisation: default_visualisation(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlaybackConfig {
+    /// Seek back to the start and keep playing when the stream ends.
+    #[serde(rename = "loop", default)]
+    pub loop_playback: bool,
+}
+
+impl Default for PlaybackConfig {
+    fn default() -> Self {
+        PlaybackConfig {
+            loop_playback: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WaterfallConfig {
+    #[serde(default = "default_waterfall_rows")]
+    pub rows: usize,
+    #[serde(default = "default_waterfall_columns")]
+    pub columns: usize,
+}
+
+impl Default for WaterfallConfig {
+    fn default() -> Self {
+        WaterfallConfig {
+            rows: default_waterfall_rows(),
+            columns: default_waterfall_columns(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub audio: AudioConfig,
+    #[serde(default)]
+    pub display: DisplayConfig,
+    #[serde(default)]
+    pub playback: PlaybackConfig,
+    #[serde(default)]
+    pub waterfall: WaterfallConfig,
+}
+
+/// Load a `Config` from a TOML file, falling back to defaults for the whole
+/// file (if it's missing or unparsable) or for any field it leaves out.
+pub fn read_config(path: &str) -> Config {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| match toml::from_str(&contents) {
+            Ok(config) => Some(config),
+            Err(err) => {
+                println!("Error: could not parse config file {}. {}", path, err);
+                None
+            }
+        })
+        .unwrap_or_default()
+}
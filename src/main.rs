@@ -4,6 +4,7 @@
 extern crate glium;
 use std::sync::mpsc;
 //use std::time::Duration;
+mod config;
 mod pipeline;
 mod waterfall;
 mod kaleidoscope;
@@ -24,6 +25,9 @@ impl Default for Visualisation {
 #[derive(Default)]
 struct State {
     file_name : Option<String>,
+    capture : Option<Option<String>>,
+    uri : Option<String>,
+    stdin : bool,
     full_screen : bool,
     visualisation: Visualisation,
 }
@@ -43,20 +47,55 @@ fn main() {
             .long("full")
             .takes_value(false)
             .help("Run full screen"))
+        .arg(Arg::with_name("config")
+            .long("config")
+            .takes_value(true)
+            .help("Path to a TOML config file"))
+        .arg(Arg::with_name("capture")
+            .long("capture")
+            .takes_value(true)
+            .min_values(0)
+            .help("Capture live audio instead of a file, optionally naming a device"))
+        .arg(Arg::with_name("uri")
+            .long("uri")
+            .takes_value(true)
+            .help("Decode audio streamed from a network URI instead of a file"))
+        .arg(Arg::with_name("stdin")
+            .long("stdin")
+            .takes_value(false)
+            .help("Decode audio read from standard input instead of a file"))
         .get_matches();
 
+    let config = matches
+        .value_of("config")
+        .map(config::read_config)
+        .unwrap_or_default();
+
     let mut state : State = State::default();
 
-    state.full_screen = matches.is_present("fullscreen");
+    state.full_screen = matches.is_present("fullscreen") || config.display.fullscreen;
     state.file_name =  matches.value_of("file").map(|f | f.to_string());
+    state.capture = if matches.is_present("capture") {
+        Some(matches.value_of("capture").map(|d| d.to_string()))
+    } else {
+        None
+    };
+    state.uri = matches.value_of("uri").map(|u| u.to_string());
+    state.stdin = matches.is_present("stdin");
+    state.visualisation = match config.display.default_visualisation.as_str() {
+        "waterfall" => Visualisation::WATERFALL,
+        _ => Visualisation::KALEIDOSCOPE,
+    };
 
-    match state.file_name {
-        Some(_) => { run_visualisation(&state); },
+    match (&state.file_name, &state.capture, &state.uri, state.stdin) {
+        (Some(_), _, _, _) | (_, Some(_), _, _) | (_, _, Some(_), _) | (_, _, _, true) => {
+            run_visualisation(&state, &config);
+        },
         _ => { println!("No file"); }
     }
 }
 
-fn run_visualisation(state : &State) {
+fn run_visualisation(state : &State, config : &config::Config) {
     gst::init().unwrap();
 
     use glium::{glutin, Surface};
@@ -76,29 +115,72 @@ fn run_visualisation(state : &State) {
     let display = glium::Display::new(wb, cb, &event_loop).unwrap();
 
     let (mpsc_sender, mpsc_receiver) = mpsc::sync_channel(22000);
+    let (message_sender, message_receiver) = mpsc::channel();
     let mut pipeline: Option<pipeline::Pipeline> = None;
-    pipeline::Pipeline::new(
-        state.file_name.as_ref().unwrap(),
-        mpsc_sender.clone(),
-    )
-    .map_err(|err| {
-        println!("Error: could not create pipeline. {}", err);
-        pipeline = Option::None;
-    })
-    .and_then(|p| {
-        p.play()
-            .map_err(|err| {
-                println!("Error: could not play. {}", err);
-                pipeline = Option::None;
-            })
-            .and_then(|_| {
-                pipeline = Option::Some(p);
-                Ok(())
-            })
-    })
-    .ok();
-
-    let mut wf = waterfall::Shader::new(&display, 80, &pipeline::FFT_SIZE/2);
+
+    let initial_pipeline = if let Some(uri) = &state.uri {
+        pipeline::Pipeline::from_uri(
+            uri.clone(),
+            config.audio.rate,
+            config.audio.fft_size,
+            pipeline::Window::from_name(&config.audio.window),
+            config.playback.loop_playback,
+            mpsc_sender.clone(),
+            message_sender.clone(),
+        )
+    } else if state.stdin {
+        use std::io::Read;
+        let mut data = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut data)
+            .map_err(|err| println!("Error: could not read stdin. {}", err))
+            .ok();
+
+        pipeline::Pipeline::from_bytes(
+            data,
+            config.audio.rate,
+            config.audio.fft_size,
+            pipeline::Window::from_name(&config.audio.window),
+            config.playback.loop_playback,
+            mpsc_sender.clone(),
+            message_sender.clone(),
+        )
+    } else {
+        let source = match &state.capture {
+            Some(device) => pipeline::Source::Capture(device.clone()),
+            None => pipeline::Source::File(state.file_name.clone().unwrap()),
+        };
+
+        pipeline::Pipeline::new(
+            source,
+            config.audio.rate,
+            config.audio.fft_size,
+            pipeline::Window::from_name(&config.audio.window),
+            config.playback.loop_playback,
+            mpsc_sender.clone(),
+            message_sender.clone(),
+        )
+    };
+
+    initial_pipeline
+        .map_err(|err| {
+            println!("Error: could not create pipeline. {}", err);
+            pipeline = Option::None;
+        })
+        .and_then(|p| {
+            p.play()
+                .map_err(|err| {
+                    println!("Error: could not play. {}", err);
+                    pipeline = Option::None;
+                })
+                .and_then(|_| {
+                    pipeline = Option::Some(p);
+                    Ok(())
+                })
+        })
+        .ok();
+
+    let mut wf = waterfall::Shader::new(&display, config.waterfall.rows, config.waterfall.columns);
     let mut ks = kaleidoscope::Shader::new(&display);
     let mut ts = texture_shader::Shader::new(&display);
 
@@ -106,6 +188,7 @@ fn run_visualisation(state : &State) {
 
     let mut current_visualisation = state.visualisation.clone();
     let mut shift_state = false;
+    let mut now_playing : Option<String> = None;
 
     event_loop.run(move |event, _, control_flow| {
         match event {
@@ -142,7 +225,15 @@ fn run_visualisation(state : &State) {
                         Some(glutin::event::VirtualKeyCode::S) => {
                             match &pipeline {
                                 None => {
-                                    pipeline::Pipeline::new(&r"resources/youve_got_speed.mp3".to_string(), mpsc_sender.clone())
+                                    pipeline::Pipeline::new(
+                                        pipeline::Source::File(r"resources/youve_got_speed.mp3".to_string()),
+                                        config.audio.rate,
+                                        config.audio.fft_size,
+                                        pipeline::Window::from_name(&config.audio.window),
+                                        config.playback.loop_playback,
+                                        mpsc_sender.clone(),
+                                        message_sender.clone(),
+                                    )
                                         .map_err(|err| {
                                             println!("Error: could not create pipeline. {}", err);
                                             pipeline = Option::None;
@@ -183,6 +274,40 @@ fn run_visualisation(state : &State) {
                                 _ => {}
                             }
                         },
+                        Some(glutin::event::VirtualKeyCode::Right) => {
+                            if let Some(p) = &pipeline {
+                                if let Some(pos) = p.position() {
+                                    p.seek(pos + gst::ClockTime::from_seconds(10))
+                                        .map_err(|err| {
+                                            println!("Error: could not seek. {}", err);
+                                        })
+                                        .ok();
+                                }
+                            }
+                        },
+                        Some(glutin::event::VirtualKeyCode::Left) => {
+                            if let Some(p) = &pipeline {
+                                if let Some(pos) = p.position() {
+                                    let target = pos
+                                        .checked_sub(gst::ClockTime::from_seconds(10))
+                                        .unwrap_or_else(|| gst::ClockTime::from_seconds(0));
+                                    p.seek(target)
+                                        .map_err(|err| {
+                                            println!("Error: could not seek. {}", err);
+                                        })
+                                        .ok();
+                                }
+                            }
+                        },
+                        Some(glutin::event::VirtualKeyCode::R) => {
+                            if let Some(p) = &pipeline {
+                                p.seek(gst::ClockTime::from_seconds(0))
+                                    .map_err(|err| {
+                                        println!("Error: could not restart. {}", err);
+                                    })
+                                    .ok();
+                            }
+                        },
                         Some(glutin::event::VirtualKeyCode::K) => {
                             current_visualisation = Visualisation::KALEIDOSCOPE;
                         },
@@ -228,6 +353,49 @@ fn run_visualisation(state : &State) {
 //            println!("fft data");
         }
 
+        if let Some(p) = &pipeline {
+            p.poll_messages();
+        }
+
+        while let Ok(message) = message_receiver.try_recv() {
+            match message {
+                pipeline::PipelineMessage::Eos => {
+                    println!("Playback finished");
+                    if let Some(p) = &pipeline {
+                        p.stop()
+                            .map_err(|err| {
+                                println!("Error: could not stop. {}", err);
+                            })
+                            .ok();
+                    }
+                    pipeline = Option::None;
+                },
+                pipeline::PipelineMessage::Error(err) => {
+                    println!("Error: {}", err);
+                    if let Some(p) = &pipeline {
+                        p.stop()
+                            .map_err(|err| {
+                                println!("Error: could not stop. {}", err);
+                            })
+                            .ok();
+                    }
+                    pipeline = Option::None;
+                },
+                // Logged to the console for now; there's no text-rendering
+                // support in the visualiser shaders yet to draw this as an
+                // on-screen overlay.
+                pipeline::PipelineMessage::Tag { title, artist, codec } => {
+                    now_playing = Some(format!(
+                        "{} - {} ({})",
+                        title.unwrap_or_else(|| "Unknown title".to_string()),
+                        artist.unwrap_or_else(|| "Unknown artist".to_string()),
+                        codec.unwrap_or_else(|| "Unknown codec".to_string()),
+                    ));
+                    println!("Now playing: {}", now_playing.as_ref().unwrap());
+                },
+            }
+        }
+
 
         let mut framebuffer = glium::framebuffer::SimpleFrameBuffer::new(&display, &wf_texture).unwrap();
 
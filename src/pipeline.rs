@@ -6,42 +6,151 @@ use gst::ElementExt;
 use rustfft::{num_complex::Complex, FftPlanner};
 use std::sync::mpsc;
 
-pub static FFT_SIZE: usize = 800;
-
 #[derive(Debug, Display, Error)]
 #[display(fmt = "Missing element {}", _0)]
 struct MissingElement(#[error(not(source))] &'static str);
 
+/// Window function applied to each FFT frame before transforming it, to
+/// trade frequency resolution against spectral leakage.
+#[derive(Debug, Clone, Copy)]
+pub enum Window {
+    Hann,
+    Hamming,
+    Rectangular,
+}
+
+impl Window {
+    pub fn from_name(name: &str) -> Window {
+        match name.to_lowercase().as_str() {
+            "hamming" => Window::Hamming,
+            "rectangular" | "none" => Window::Rectangular,
+            _ => Window::Hann,
+        }
+    }
+
+    fn coefficients(self, size: usize) -> Vec<f32> {
+        match self {
+            Window::Hann => (0..size)
+                .map(|n| {
+                    0.5 * (1.0
+                        - (2.0 * std::f32::consts::PI * n as f32 / (size as f32 - 1.0)).cos())
+                })
+                .collect(),
+            Window::Hamming => (0..size)
+                .map(|n| {
+                    0.54 - 0.46
+                        * (2.0 * std::f32::consts::PI * n as f32 / (size as f32 - 1.0)).cos()
+                })
+                .collect(),
+            Window::Rectangular => vec![1.0_f32; size],
+        }
+    }
+}
+
+/// Out-of-band events surfaced from the GStreamer bus, for the window to
+/// display rather than letting them vanish inside the pipeline thread.
+#[derive(Debug, Clone)]
+pub enum PipelineMessage {
+    Eos,
+    Error(String),
+    Tag {
+        title: Option<String>,
+        artist: Option<String>,
+        codec: Option<String>,
+    },
+}
+
+/// Where the pipeline should pull its audio from.
+pub enum Source {
+    /// Decode a file on disk.
+    File(String),
+    /// Capture live audio, optionally from a specific device (e.g. an ALSA
+    /// device name); `None` lets GStreamer pick the platform default.
+    Capture(Option<String>),
+    /// Decode audio already sitting in memory (an embedded resource, a
+    /// completed download, piped stdin, ...).
+    Bytes(Vec<u8>),
+    /// Decode audio streamed from a network URI.
+    Uri(String),
+}
+
+/// Link a decoder's newly-appeared source pad into the tee, once it turns
+/// out to carry raw audio. Shared by every `Source` variant that exposes its
+/// pad dynamically (`decodebin`, `uridecodebin`), since none of them know
+/// what the pad's caps will be until the stream has been sniffed.
+fn link_decoded_pad_to_tee(tee: &gst::Element, src_pad: &gst::Pad) {
+    let caps = match src_pad.get_current_caps() {
+        Some(caps) => caps,
+        None => return,
+    };
+    let structure = match caps.get_structure(0) {
+        Some(s) => s,
+        None => return,
+    };
+
+    if !structure.get_name().starts_with("audio/x-raw") {
+        return;
+    }
+
+    let sink_pad = match tee.get_static_pad("sink") {
+        Some(pad) => pad,
+        None => return,
+    };
+
+    if sink_pad.is_linked() {
+        return;
+    }
+
+    if let Err(err) = src_pad.link(&sink_pad) {
+        eprintln!("Error: could not link decoded pad to tee. {:?}", err);
+    }
+}
+
 pub struct Pipeline {
     gstreamer_pipeline: gst::Pipeline,
+    bus: gst::Bus,
+    loop_playback: bool,
+    message_sender: mpsc::Sender<PipelineMessage>,
 }
 
 impl Pipeline {
     pub fn new(
-        file_name: &std::string::String,
+        source: Source,
+        rate: i32,
+        fft_size: usize,
+        window: Window,
+        loop_playback: bool,
         sender: mpsc::SyncSender<Vec<f64>>,
+        message_sender: mpsc::Sender<PipelineMessage>,
     ) -> Result<Pipeline, Error> {
         let mut planner = FftPlanner::<f32>::new();
-        let fft = planner.plan_fft_forward(FFT_SIZE);
+        let fft = planner.plan_fft_forward(fft_size);
         let mut fft_buffer = vec![
             Complex {
                 re: 0.0_f32,
                 im: 0.0_f32
             };
-            FFT_SIZE
+            fft_size
         ];
         let mut pos: usize = 0;
 
+        // Precompute the window coefficients once; the coherent gain they
+        // introduce is compensated for when scaling the FFT magnitudes below.
+        let window_coefficients = window.coefficients(fft_size);
+        let window_gain: f32 = window_coefficients.iter().sum();
+
+        let gstreamer_pipeline = gst::Pipeline::new(Option::None);
+        let bus = gstreamer_pipeline
+            .get_bus()
+            .ok_or_else(|| MissingElement("bus"))?;
+
         let pipeline = Pipeline {
-            gstreamer_pipeline: gst::Pipeline::new(Option::None),
+            gstreamer_pipeline,
+            bus,
+            loop_playback,
+            message_sender,
         };
 
-        let filesrc = gst::ElementFactory::make("filesrc", Option::None)
-            .map_err(|_| MissingElement("src"))?;
-        let mpeg_audio_parse = gst::ElementFactory::make("mpegaudioparse", Option::None)
-            .map_err(|_| MissingElement("mpegaudioparse"))?;
-        let mpg_audio_dec = gst::ElementFactory::make("mpg123audiodec", Option::None)
-            .map_err(|_| MissingElement("mpg123audiodec"))?;
         let tee =
             gst::ElementFactory::make("tee", Option::None).map_err(|_| MissingElement("tee"))?;
 
@@ -63,15 +172,12 @@ impl Pipeline {
         let app_sink = gst::ElementFactory::make("appsink", Option::None)
             .map_err(|_| MissingElement("app_sink"))?;
 
-        filesrc.set_property("location", &file_name)?;
-
         // Appsink andle S16 mono at a convenient sample rate.
         let caps = gst::Caps::new_simple(
             "audio/x-raw",
             &[
                 ("format", &gst_audio::AUDIO_FORMAT_S16.to_str()),
-                ("rate", &11025i32),
-//                ("rate", &200i32),
+                ("rate", &rate),
                 ("channels", &1i32),
                 ("layout", &"non-interleaved"),
             ],
@@ -79,11 +185,7 @@ impl Pipeline {
 
         app_sink.set_property("caps", &caps)?;
 
-
         let elements = &[
-            &filesrc,
-            &mpeg_audio_parse,
-            &mpg_audio_dec,
             &tee,
             &audio_queue,
             &audio_convert,
@@ -95,11 +197,9 @@ impl Pipeline {
             &app_sink,
         ];
 
-        let decode_pipeline = &[&filesrc, &mpeg_audio_parse, &mpg_audio_dec, &tee];
         let audio_pipeline = &[&audio_queue, &audio_convert, &audio_resample, &audio_sink];
         let app_pipeline = &[&app_queue, &app_convert, &app_resample, &app_sink];
         pipeline.gstreamer_pipeline.add_many(elements)?;
-        gst::Element::link_many(decode_pipeline)?;
         gst::Element::link_many(audio_pipeline)?;
         gst::Element::link_many(app_pipeline)?;
 
@@ -111,6 +211,96 @@ impl Pipeline {
         let queue_app_pad = app_queue.get_static_pad("sink").unwrap();
         tee_app_pad.link(&queue_app_pad)?;
 
+        match source {
+            Source::File(file_name) => {
+                let filesrc = gst::ElementFactory::make("filesrc", Option::None)
+                    .map_err(|_| MissingElement("src"))?;
+                let decodebin = gst::ElementFactory::make("decodebin", Option::None)
+                    .map_err(|_| MissingElement("decodebin"))?;
+                filesrc.set_property("location", &file_name)?;
+
+                pipeline
+                    .gstreamer_pipeline
+                    .add_many(&[&filesrc, &decodebin])?;
+                gst::Element::link_many(&[&filesrc, &decodebin])?;
+
+                // decodebin only exposes its source pad once it has sniffed the
+                // stream, so the link into the tee has to happen dynamically
+                // rather than up front.
+                let tee_sink = tee.clone();
+                decodebin
+                    .connect_pad_added(move |_, src_pad| link_decoded_pad_to_tee(&tee_sink, src_pad));
+            }
+            Source::Bytes(data) => {
+                let appsrc = gst::ElementFactory::make("appsrc", Option::None)
+                    .map_err(|_| MissingElement("appsrc"))?;
+                let decodebin = gst::ElementFactory::make("decodebin", Option::None)
+                    .map_err(|_| MissingElement("decodebin"))?;
+
+                let app_src = appsrc
+                    .clone()
+                    .dynamic_cast::<gst_app::AppSrc>()
+                    .expect("Source element is expected to be an appsrc!");
+                app_src.set_format(gst::Format::Bytes);
+                app_src.set_size(data.len() as i64);
+
+                pipeline
+                    .gstreamer_pipeline
+                    .add_many(&[&appsrc, &decodebin])?;
+                gst::Element::link_many(&[&appsrc, &decodebin])?;
+
+                // Feed the whole in-memory buffer to appsrc in chunks as it asks
+                // for data, then signal end-of-stream once it's exhausted.
+                let mut remaining = data;
+                app_src.set_callbacks(
+                    gst_app::AppSrcCallbacks::builder()
+                        .need_data(move |app_src, _| {
+                            if remaining.is_empty() {
+                                let _ = app_src.end_of_stream();
+                                return;
+                            }
+
+                            let chunk_len = remaining.len().min(4096);
+                            let chunk: Vec<u8> = remaining.drain(..chunk_len).collect();
+                            let _ = app_src.push_buffer(gst::Buffer::from_slice(chunk));
+                        })
+                        .build(),
+                );
+
+                let tee_sink = tee.clone();
+                decodebin
+                    .connect_pad_added(move |_, src_pad| link_decoded_pad_to_tee(&tee_sink, src_pad));
+            }
+            Source::Uri(uri) => {
+                let uridecodebin = gst::ElementFactory::make("uridecodebin", Option::None)
+                    .map_err(|_| MissingElement("uridecodebin"))?;
+                uridecodebin.set_property("uri", &uri)?;
+
+                pipeline.gstreamer_pipeline.add(&uridecodebin)?;
+
+                let tee_sink = tee.clone();
+                uridecodebin
+                    .connect_pad_added(move |_, src_pad| link_decoded_pad_to_tee(&tee_sink, src_pad));
+            }
+            Source::Capture(device) => {
+                // Live capture sources already produce raw audio, so they can
+                // link straight into the tee without going through decodebin.
+                let capture_src = match &device {
+                    Some(_) => gst::ElementFactory::make("alsasrc", Option::None)
+                        .map_err(|_| MissingElement("alsasrc"))?,
+                    None => gst::ElementFactory::make("autoaudiosrc", Option::None)
+                        .map_err(|_| MissingElement("autoaudiosrc"))?,
+                };
+
+                if let Some(device) = device {
+                    capture_src.set_property("device", &device)?;
+                }
+
+                pipeline.gstreamer_pipeline.add(&capture_src)?;
+                capture_src.link(&tee)?;
+            }
+        }
+
         let appsink = app_sink
             .dynamic_cast::<gst_app::AppSink>()
             .expect("Sink element is expected to be an appsink!");
@@ -167,17 +357,17 @@ impl Pipeline {
                     })?;
 
                     for sample in samples {
-                        if pos >= FFT_SIZE {
+                        if pos >= fft_size {
                             fft.process(&mut fft_buffer);
                             pos = 0;
                             sender
                                 .send(
                                     fft_buffer
                                         .iter()
-                                        .skip(FFT_SIZE / 2)
+                                        .skip(fft_size / 2)
                                         .map(|v| {
                                             let x = 1.0 +
-                                                ((v.norm() as f64) / FFT_SIZE as f64).log10();
+                                                ((v.norm() as f64) / window_gain as f64).log10();
                                             if x < 0.0 {
                                                 0.0
                                             } else {
@@ -188,7 +378,8 @@ impl Pipeline {
                                 )
                                 .unwrap();
                         }
-                        fft_buffer[pos] = Complex::new(*sample as f32, 0.0 as f32);
+                        fft_buffer[pos] =
+                            Complex::new(*sample as f32 * window_coefficients[pos], 0.0 as f32);
                         pos += 1;
                     }
 
@@ -200,6 +391,126 @@ impl Pipeline {
         Ok(pipeline)
     }
 
+    /// Drain any bus messages that have arrived since the last call and act
+    /// on them. `gst::Bus::add_watch` would need an actively-iterated GLib
+    /// main loop to ever fire, but this app is driven by the winit/glutin
+    /// event loop instead, so the bus is polled synchronously once per frame
+    /// from there (see `run_visualisation`).
+    pub fn poll_messages(&self) {
+        use gst::MessageType;
+        use gst::MessageView;
+
+        while let Some(msg) = self.bus.pop_filtered(&[
+            MessageType::Eos,
+            MessageType::Error,
+            MessageType::StateChanged,
+            MessageType::Tag,
+        ]) {
+            match msg.view() {
+                MessageView::Eos(..) => {
+                    if self.loop_playback {
+                        if let Err(err) = self.seek(gst::ClockTime::from_seconds(0)) {
+                            eprintln!("Error: could not loop playback. {}", err);
+                        }
+                    } else {
+                        self.message_sender.send(PipelineMessage::Eos).ok();
+                    }
+                }
+                MessageView::Error(err) => {
+                    self.message_sender
+                        .send(PipelineMessage::Error(format!(
+                            "Error from {:?}: {} ({:?})",
+                            err.get_src().map(|s| s.get_path_string()),
+                            err.get_error(),
+                            err.get_debug()
+                        )))
+                        .ok();
+                }
+                MessageView::StateChanged(state_changed) => {
+                    if state_changed
+                        .get_src()
+                        .map(|s| s == self.gstreamer_pipeline)
+                        .unwrap_or(false)
+                    {
+                        println!(
+                            "Pipeline state changed from {:?} to {:?}",
+                            state_changed.get_old(),
+                            state_changed.get_current()
+                        );
+                    }
+                }
+                MessageView::Tag(tag) => {
+                    let tags = tag.get_tags();
+                    let title = tags
+                        .get::<gst::tags::Title>()
+                        .and_then(|t| t.get().map(|v| v.to_string()));
+                    let artist = tags
+                        .get::<gst::tags::Artist>()
+                        .and_then(|t| t.get().map(|v| v.to_string()));
+                    let codec = tags
+                        .get::<gst::tags::AudioCodec>()
+                        .and_then(|t| t.get().map(|v| v.to_string()));
+
+                    if title.is_some() || artist.is_some() || codec.is_some() {
+                        self.message_sender
+                            .send(PipelineMessage::Tag {
+                                title,
+                                artist,
+                                codec,
+                            })
+                            .ok();
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Decode audio already sitting in memory (an embedded resource, a
+    /// completed download, piped stdin, ...) through the same tee/FFT/sink
+    /// topology as [`Pipeline::new`].
+    pub fn from_bytes(
+        data: Vec<u8>,
+        rate: i32,
+        fft_size: usize,
+        window: Window,
+        loop_playback: bool,
+        sender: mpsc::SyncSender<Vec<f64>>,
+        message_sender: mpsc::Sender<PipelineMessage>,
+    ) -> Result<Pipeline, Error> {
+        Pipeline::new(
+            Source::Bytes(data),
+            rate,
+            fft_size,
+            window,
+            loop_playback,
+            sender,
+            message_sender,
+        )
+    }
+
+    /// Decode audio streamed from a network URI through the same
+    /// tee/FFT/sink topology as [`Pipeline::new`].
+    pub fn from_uri(
+        uri: String,
+        rate: i32,
+        fft_size: usize,
+        window: Window,
+        loop_playback: bool,
+        sender: mpsc::SyncSender<Vec<f64>>,
+        message_sender: mpsc::Sender<PipelineMessage>,
+    ) -> Result<Pipeline, Error> {
+        Pipeline::new(
+            Source::Uri(uri),
+            rate,
+            fft_size,
+            window,
+            loop_playback,
+            sender,
+            message_sender,
+        )
+    }
+
 //    pub fn get_current_state(&self) -> gst::State {
 //        self.gstreamer_pipeline.get_current_state()
 //    }
@@ -218,4 +529,18 @@ impl Pipeline {
         self.gstreamer_pipeline.set_state(gst::State::Null)?;
         Ok(())
     }
+
+    pub fn seek(&self, position: gst::ClockTime) -> Result<(), Error> {
+        self.gstreamer_pipeline
+            .seek_simple(gst::SeekFlags::FLUSH | gst::SeekFlags::KEY_UNIT, position)?;
+        Ok(())
+    }
+
+    pub fn position(&self) -> Option<gst::ClockTime> {
+        self.gstreamer_pipeline.query_position(gst::Format::Time)
+    }
+
+    pub fn duration(&self) -> Option<gst::ClockTime> {
+        self.gstreamer_pipeline.query_duration(gst::Format::Time)
+    }
 }